@@ -7,6 +7,12 @@
 //! Let's say we have a template we'd like to use on the page multiple times. We also have styles
 //! related to this template inside an embedded `<style>` tag.
 //!
+//! `css_once!` doesn't emit the `<style>` tag at its point of use — it only registers the css the
+//! first time each template type is seen. Render the body first, then call
+//! [`CssOnce::render_styles`] once, to get everything collected so far as a single `<style>` tag,
+//! e.g. to drop into `<head>`. That's the standard SSR layout: all component styles end up in one
+//! place instead of scattered through `<body>`.
+//!
 //! ```rust
 //! use markup_css_once::{CssOnce, css_once};
 //!
@@ -23,33 +29,150 @@
 //!     }
 //! }
 //!
-//! // We need an tracker for components with already rendered css
+//! // We need a tracker for the css collected while rendering
 //! let css = CssOnce::new();
 //!
-//! // The first time the template is rendered with styles
+//! // The body never contains a `<style>` tag, no matter how many times it's rendered
+//! let body = Hello { css: &css, name: "World" }.to_string();
+//! assert_eq!(body, "<p>Hello, <b>World</b></p>");
+//! assert_eq!(Hello { css: &css, name: "World" }.to_string(), body);
+//!
+//! // Everything collected so far can be rendered as a single tag, e.g. into `<head>`
 //! assert_eq!(
-//!     Hello { css: &css, name: "World" }.to_string(),
-//!     "<style>p { background: blue }b { color: yellow }</style>\n<p>Hello, <b>World</b></p>"
+//!     css.render_styles(),
+//!     "<style>p { background: blue }b { color: yellow }</style>\n"
 //! );
+//! ```
+//!
+//! ## Multi-threaded rendering
+//!
+//! [`CssOnce`] isn't [`Sync`], since it's backed by a [`Cell`]. If templates sharing the same
+//! tracker may be rendered from more than one thread at once (a thread pool, or a tracker stashed
+//! in shared app state), use [`SyncCssOnce`] instead. It's backed by a [`Mutex`] and gives the
+//! same "rendered exactly once" guarantee even when two threads race on the same template. Both
+//! trackers implement [`CssTracker`], and `css_once!` is generic over it, so the same template
+//! code compiles against either one.
+//!
+//! ## Scoping class names
 //!
-//! // But all subsequent calls will render only it's HTML
+//! Embedded css is rendered as-is, so two templates that happen to share a class name (e.g.
+//! `.active`) will stomp on each other once both are on the page. [`scoped_css_once!`] rewrites
+//! a chosen set of class names with a suffix derived from the template type, and hands the
+//! rewritten names back so the template body can reference them:
+//!
+//! ```rust
+//! use markup_css_once::{CssOnce, scoped_css_once};
+//!
+//! markup::define! {
+//!     Badge<'a>(css: &'a CssOnce) {
+//!         @let (style, classes) = scoped_css_once!(css,
+//!             active;
+//!             ".active { color: green }"
+//!         );
+//!         @style
+//!         div[class = classes.active] { "Online" }
+//!     }
+//! }
+//! ```
+//!
+//! ## Email-safe inlining
+//!
+//! Many email clients strip `<style>` tags, which would silently drop every component's styles.
+//! [`CssOnce::inline`] takes the rendered body plus the css collected so far and folds each
+//! matching rule's declarations into the element's `style` attribute instead, so the same
+//! templates can target both web and email:
+//!
+//! ```rust
+//! use markup_css_once::{CssOnce, css_once};
+//!
+//! markup::define! {
+//!     Hello<'a>(css: &'a CssOnce, name: &'a str) {
+//!         @css_once!(css, "p.greeting { color: blue }")
+//!         p.greeting { "Hello, " @name }
+//!     }
+//! }
+//!
+//! let css = CssOnce::new();
+//! let body = Hello { css: &css, name: "World" }.to_string();
 //! assert_eq!(
-//!     Hello { css: &css, name: "World" }.to_string(),
-//!     "<p>Hello, <b>World</b></p>"
+//!     css.inline(&body),
+//!     r#"<p class="greeting" style="color: blue">Hello, World</p>"#
 //! );
 //! ```
 //!
+//! The selector matcher supports tag names, `.class`, `#id` and comma-separated groups (e.g.
+//! `h1, h2.title`), but not combinators like descendant or child selectors. There's no
+//! CSS-specificity weighting by selector type: rules are applied in source order and a later
+//! declaration for the same property simply overwrites an earlier one, no matter which of the
+//! matching rules is "more specific". Any pre-existing inline `style` on the element always has
+//! the highest precedence.
+//!
+//! ## Loading css from a file
+//!
+//! [`css_once_file!`] works like `css_once!`, but loads the stylesheet from a `.css` file
+//! (relative to the current source file) via `include_str!` at compile time, so teams can keep
+//! real `.css` files with editor tooling/linting instead of inlining css in Rust strings:
+//!
+//! ```ignore
+//! css_once_file!(css, "hello.css")
+//! ```
+//!
 //! [Markup]: https://github.com/utkarshkukreti/markup.rs
+//! [`Mutex`]: std::sync::Mutex
 
 #![warn(clippy::all, missing_docs, nonstandard_style, future_incompatible)]
 
 use std::any::type_name;
 use std::cell::Cell;
 use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Common contract for css trackers: remembers, per template type, whether its styles have
+/// already been seen, and registers their css for later retrieval
+///
+/// Implemented by [`CssOnce`] and [`SyncCssOnce`]. `css_once!` is generic over this trait, so the
+/// same template compiles against either tracker.
+pub trait CssTracker {
+    /// Checks if styles for template `T` are already rendered, marking them as rendered if not
+    fn is_rendered<T>(&self) -> bool;
+
+    /// Registers `css` as the styles for template `T`, unless it was already registered
+    fn register<T>(&self, css: &'static str);
+}
+
+/// Css collected by a tracker: which template types have been seen, and the css text collected
+/// for them so far, in first-seen order
+#[derive(Default)]
+struct Collected {
+    seen: HashSet<&'static str>,
+    css: Vec<String>,
+}
+
+impl Collected {
+    fn is_rendered<T>(&mut self) -> bool {
+        !self.seen.insert(type_name::<T>())
+    }
+
+    fn register<T>(&mut self, css: &'static str) {
+        if self.seen.insert(type_name::<T>()) {
+            self.css.push(css.to_string());
+        }
+    }
+
+    fn render_styles(&self) -> String {
+        if self.css.is_empty() {
+            String::new()
+        } else {
+            format!("<style>{}</style>\n", self.css.concat())
+        }
+    }
+}
 
 /// A tracker for rendered css
 #[derive(Default)]
-pub struct CssOnce(Cell<HashSet<&'static str>>);
+pub struct CssOnce(Cell<Collected>);
 
 impl CssOnce {
     /// Creates a new instance of the tracker
@@ -57,23 +180,523 @@ impl CssOnce {
         Self::default()
     }
 
-    /// Checks if styles for template `T` is already rendered
-    pub fn is_rendered<T>(&self) -> bool {
+    /// Concatenates all the css collected so far into a single `<style>` tag, in first-seen order
+    ///
+    /// "First-seen" is about render order, not declaration order: whichever template type is
+    /// rendered first contributes its css first, regardless of the order the templates are
+    /// defined in.
+    ///
+    /// ```rust
+    /// use markup_css_once::{CssOnce, css_once};
+    ///
+    /// markup::define! {
+    ///     First<'a>(css: &'a CssOnce) {
+    ///         @css_once!(css, "p { color: red }")
+    ///     }
+    ///     Second<'a>(css: &'a CssOnce) {
+    ///         @css_once!(css, "b { color: blue }")
+    ///     }
+    /// }
+    ///
+    /// let css = CssOnce::new();
+    /// Second { css: &css }.to_string();
+    /// First { css: &css }.to_string();
+    ///
+    /// assert_eq!(
+    ///     css.render_styles(),
+    ///     "<style>b { color: blue }p { color: red }</style>\n"
+    /// );
+    /// ```
+    pub fn render_styles(&self) -> String {
+        let inner = self.0.take();
+        let rendered = inner.render_styles();
+        self.0.set(inner);
+        rendered
+    }
+
+    /// Folds the css collected so far into the matching elements' `style` attribute of `html`,
+    /// instead of a `<style>` tag
+    ///
+    /// Intended for email, where many clients strip `<style>` tags. See the [crate-level
+    /// docs](crate) for what selectors are supported and how conflicts are resolved.
+    pub fn inline(&self, html: &str) -> String {
+        let inner = self.0.take();
+        let css = inner.css.concat();
+        self.0.set(inner);
+        inline_styles(html, &css)
+    }
+}
+
+impl CssTracker for CssOnce {
+    fn is_rendered<T>(&self) -> bool {
         let mut inner = self.0.take();
-        let inserted = inner.insert(type_name::<T>());
+        let result = inner.is_rendered::<T>();
+        self.0.set(inner);
+        result
+    }
+
+    fn register<T>(&self, css: &'static str) {
+        let mut inner = self.0.take();
+        inner.register::<T>(css);
         self.0.set(inner);
-        !inserted
+    }
+}
+
+/// A thread-safe tracker for rendered css, backed by a [`Mutex`]
+///
+/// Use this instead of [`CssOnce`] when templates sharing the same tracker may be rendered from
+/// more than one thread at once, e.g. behind a web framework's thread pool. The "rendered exactly
+/// once" guarantee holds even when two threads race on the same template: only one of them will
+/// ever see `is_rendered` return `false`.
+#[derive(Default)]
+pub struct SyncCssOnce(Mutex<Collected>);
+
+impl SyncCssOnce {
+    /// Creates a new instance of the tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Concatenates all the css collected so far into a single `<style>` tag, in first-seen order
+    pub fn render_styles(&self) -> String {
+        self.0.lock().unwrap().render_styles()
+    }
+
+    /// Folds the css collected so far into the matching elements' `style` attribute of `html`,
+    /// instead of a `<style>` tag
+    ///
+    /// See [`CssOnce::inline`] for details.
+    pub fn inline(&self, html: &str) -> String {
+        let css = self.0.lock().unwrap().css.concat();
+        inline_styles(html, &css)
+    }
+}
+
+impl CssTracker for SyncCssOnce {
+    fn is_rendered<T>(&self) -> bool {
+        self.0.lock().unwrap().is_rendered::<T>()
+    }
+
+    fn register<T>(&self, css: &'static str) {
+        self.0.lock().unwrap().register::<T>(css)
     }
 }
 
 /// A macro for the Markup templates to ensure the css is rendered only once
+///
+/// Rather than emitting the `<style>` tag at the point of use, this registers `css` with the
+/// tracker the first time template `Self` is seen and always renders empty markup. Call
+/// `render_styles` on the tracker (e.g. once the body is done rendering) to get everything
+/// collected so far as a single `<style>` tag.
 #[macro_export]
 macro_rules! css_once {
-    ($css:ident, $($str:tt)+) => {
-        if CssOnce::is_rendered::<Self>($css) {
-            markup::raw("")
+    ($css:ident, $($str:tt)+) => {{
+        $crate::CssTracker::register::<Self>(*$css, concat!($($str),+));
+        markup::raw("")
+    }};
+}
+
+/// Like [`css_once!`], but loads the stylesheet from a `.css` file instead of a string literal
+///
+/// The path is resolved relative to the current source file at compile time via `include_str!`,
+/// so there's zero runtime file I/O and the same render-once-per-type guarantee as `css_once!`.
+/// This lets the stylesheet live in a real `.css` file with editor tooling and linting, instead
+/// of being inlined as a Rust string.
+///
+/// ```ignore
+/// css_once_file!(css, "hello.css")
+/// ```
+///
+/// See `tests/css_once_file.rs` for a compiled, executed example (a doctest can't exercise this
+/// macro directly: `include_str!`'s path is resolved against the doctest's generated temp file,
+/// not this crate, so there's no fixture to point it at).
+#[macro_export]
+macro_rules! css_once_file {
+    ($css:ident, $path:literal) => {{
+        $crate::CssTracker::register::<Self>(*$css, include_str!($path));
+        markup::raw("")
+    }};
+}
+
+/// Computes a short, deterministic per-type suffix used to scope class names
+///
+/// Not part of the public API, exposed only for [`scoped_css_once!`] to call.
+#[doc(hidden)]
+pub fn scope_suffix<T>() -> String {
+    let mut hasher = DefaultHasher::new();
+    type_name::<T>().hash(&mut hasher);
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+/// Rewrites every occurrence of `.<class>` for `class` in `classes` to `.<class>-<suffix>`,
+/// leaving tags, pseudo-classes and any other identifier untouched
+///
+/// Not part of the public API, exposed only for [`scoped_css_once!`] to call.
+///
+/// ```rust
+/// use markup_css_once::rewrite_classes;
+///
+/// // Only the listed `.active` class is rewritten: the tag (`div`), the pseudo-class (`:hover`)
+/// // and the unlisted `.inactive` class are all left untouched.
+/// let css = "div.active:hover { color: green } .inactive { color: grey }";
+/// assert_eq!(
+///     rewrite_classes(css, &["active"], "ab12cd34"),
+///     "div.active-ab12cd34:hover { color: green } .inactive { color: grey }"
+/// );
+/// ```
+#[doc(hidden)]
+pub fn rewrite_classes(css: &str, classes: &[&str], suffix: &str) -> String {
+    fn is_ident_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '-'
+    }
+
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while !rest.is_empty() {
+        if rest.starts_with('.') {
+            let after_dot = &rest[1..];
+            let matched = classes.iter().find(|class| match after_dot.strip_prefix(**class) {
+                Some(tail) => !tail.chars().next().map(is_ident_char).unwrap_or(false),
+                None => false,
+            });
+            if let Some(class) = matched {
+                out.push('.');
+                out.push_str(class);
+                out.push('-');
+                out.push_str(suffix);
+                rest = &after_dot[class.len()..];
+                continue;
+            }
+        }
+        let ch = rest.chars().next().unwrap();
+        out.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    out
+}
+
+/// Renders a template's css once, like [`css_once!`], but rewrites a given set of class names
+/// with a suffix derived from the template type, so two templates can reuse the same class name
+/// without colliding
+///
+/// Unlike `css_once!`, this emits the `<style>` tag at its point of use the first time template
+/// `Self` is seen. The css isn't registered with the tracker, so it's not included in
+/// `render_styles` or `inline`.
+///
+/// Takes the tracker, a space-separated list of class-name identifiers to scope, a `;`, and the
+/// css bits to concatenate (same as `css_once!`). Expands to a `(markup::Raw<String>, _)` tuple:
+/// the first element renders the (possibly empty) `<style>` tag, the second is a struct with one
+/// `String` field per listed class, holding its scoped name, e.g. `classes.active`.
+///
+/// The suffix is deterministic per template type, so repeated renders and the class references
+/// it hands back always agree. Only the listed classes are rewritten; everything else in the css
+/// is left untouched.
+#[macro_export]
+macro_rules! scoped_css_once {
+    ($css:ident, $($class:ident)+; $($str:tt)+) => {{
+        struct ScopedClasses {
+            $($class: String,)+
+        }
+
+        let suffix = $crate::scope_suffix::<Self>();
+        $(let $class = format!("{}-{}", stringify!($class), suffix);)+
+
+        let style = if $crate::CssTracker::is_rendered::<Self>(*$css) {
+            markup::raw(String::new())
         } else {
-            markup::raw(concat!("<style>", $($str),+, "</style>\n"))
+            let rewritten = $crate::rewrite_classes(
+                concat!($($str),+),
+                &[$(stringify!($class)),+],
+                &suffix,
+            );
+            markup::raw(format!("<style>{}</style>\n", rewritten))
+        };
+
+        (style, ScopedClasses { $($class),+ })
+    }};
+}
+
+/// A css rule: the selector groups it was declared for, and its raw `prop: value; ...` body
+struct Rule {
+    selectors: Vec<Selector>,
+    declarations: String,
+}
+
+/// A single compound selector, e.g. `div.card#featured`
+#[derive(Default)]
+struct Selector {
+    tag: Option<String>,
+    classes: Vec<String>,
+    id: Option<String>,
+}
+
+/// An html element's tag, classes and id, parsed from its opening tag
+struct Element<'a> {
+    tag: &'a str,
+    classes: Vec<&'a str>,
+    id: Option<&'a str>,
+}
+
+fn selector_matches(selector: &Selector, element: &Element) -> bool {
+    if let Some(tag) = &selector.tag {
+        if !tag.eq_ignore_ascii_case(element.tag) {
+            return false;
         }
+    }
+    if let Some(id) = &selector.id {
+        if element.id != Some(id.as_str()) {
+            return false;
+        }
+    }
+    selector
+        .classes
+        .iter()
+        .all(|class| element.classes.contains(&class.as_str()))
+}
+
+fn parse_selector(text: &str) -> Selector {
+    let mut selector = Selector::default();
+    let mut rest = text.trim();
+
+    let end = rest.find(['.', '#']).unwrap_or(rest.len());
+    if end > 0 {
+        selector.tag = Some(rest[..end].to_string());
+    }
+    rest = &rest[end..];
+
+    while let Some(marker) = rest.chars().next() {
+        let body = &rest[marker.len_utf8()..];
+        let end = body.find(['.', '#']).unwrap_or(body.len());
+        let name = &body[..end];
+        match marker {
+            '.' => selector.classes.push(name.to_string()),
+            '#' => selector.id = Some(name.to_string()),
+            _ => {}
+        }
+        rest = &body[end..];
+    }
+
+    selector
+}
+
+fn parse_rules(css: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    let mut rest = css;
+    while let Some(open) = rest.find('{') {
+        let selector_text = &rest[..open];
+        let after_open = &rest[open + 1..];
+        match after_open.find('}') {
+            Some(close) => {
+                rules.push(Rule {
+                    selectors: selector_text.split(',').map(parse_selector).collect(),
+                    declarations: after_open[..close].trim().to_string(),
+                });
+                rest = &after_open[close + 1..];
+            }
+            None => break,
+        }
+    }
+    rules
+}
+
+fn parse_declarations(text: &str) -> Vec<(String, String)> {
+    text.split(';')
+        .filter_map(|decl| {
+            let (prop, value) = decl.split_once(':')?;
+            let prop = prop.trim();
+            let value = value.trim();
+            if prop.is_empty() || value.is_empty() {
+                None
+            } else {
+                Some((prop.to_string(), value.to_string()))
+            }
+        })
+        .collect()
+}
+
+fn upsert_declaration(declarations: &mut Vec<(String, String)>, prop: String, value: String) {
+    match declarations.iter_mut().find(|(p, _)| *p == prop) {
+        Some(entry) => entry.1 = value,
+        None => declarations.push((prop, value)),
+    }
+}
+
+fn render_declarations(declarations: &[(String, String)]) -> String {
+    declarations
+        .iter()
+        .map(|(prop, value)| format!("{prop}: {value}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Splits the content of an opening tag (everything between `<` and `>`, minus a trailing `/`)
+/// into `(name, value)` attribute tokens, respecting quoted values
+fn tokenize_tag(text: &str) -> Vec<(String, Option<String>)> {
+    let bytes = text.as_bytes();
+    let len = bytes.len();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < len {
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let key_start = i;
+        while i < len && bytes[i] != b'=' && !bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == key_start {
+            break;
+        }
+        let key = text[key_start..i].to_string();
+
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= len || bytes[i] != b'=' {
+            tokens.push((key, None));
+            continue;
+        }
+        i += 1;
+        while i < len && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let value = if i < len && (bytes[i] == b'"' || bytes[i] == b'\'') {
+            let quote = bytes[i];
+            i += 1;
+            let value_start = i;
+            while i < len && bytes[i] != quote {
+                i += 1;
+            }
+            let value = text[value_start..i].to_string();
+            if i < len {
+                i += 1;
+            }
+            value
+        } else {
+            let value_start = i;
+            while i < len && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            text[value_start..i].to_string()
+        };
+        tokens.push((key, Some(value)));
+    }
+
+    tokens
+}
+
+/// Finds the index of the unquoted `>` that closes the tag starting at the beginning of `text`
+fn find_tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, b) in text.bytes().enumerate() {
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'>' => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+fn rewrite_tag(tag: &str, rules: &[Rule]) -> String {
+    let inner = &tag[1..tag.len() - 1];
+    let (inner, trailing_slash) = match inner.strip_suffix('/') {
+        Some(rest) => (rest.trim_end(), "/"),
+        None => (inner, ""),
     };
+
+    let mut tokens = tokenize_tag(inner);
+    if tokens.is_empty() {
+        return tag.to_string();
+    }
+    let name = tokens.remove(0).0;
+
+    let mut classes = Vec::new();
+    let mut id = None;
+    for (key, value) in &tokens {
+        match (key.to_ascii_lowercase().as_str(), value) {
+            ("class", Some(value)) => classes = value.split_whitespace().collect::<Vec<_>>(),
+            ("id", Some(value)) => id = Some(value.as_str()),
+            _ => {}
+        }
+    }
+
+    let element = Element { tag: &name, classes, id };
+    let mut declarations = Vec::new();
+    for rule in rules {
+        if rule.selectors.iter().any(|s| selector_matches(s, &element)) {
+            for (prop, value) in parse_declarations(&rule.declarations) {
+                upsert_declaration(&mut declarations, prop, value);
+            }
+        }
+    }
+
+    let mut wrote_style = false;
+    let mut out = format!("<{name}");
+    for (key, value) in &tokens {
+        if key.eq_ignore_ascii_case("style") {
+            if let Some(existing) = value {
+                for (prop, value) in parse_declarations(existing) {
+                    upsert_declaration(&mut declarations, prop, value);
+                }
+            }
+            if !declarations.is_empty() {
+                out.push_str(&format!(" style=\"{}\"", render_declarations(&declarations)));
+            }
+            wrote_style = true;
+            continue;
+        }
+        match value {
+            Some(value) => out.push_str(&format!(" {key}=\"{value}\"")),
+            None => out.push_str(&format!(" {key}")),
+        }
+    }
+    if !wrote_style && !declarations.is_empty() {
+        out.push_str(&format!(" style=\"{}\"", render_declarations(&declarations)));
+    }
+    out.push_str(trailing_slash);
+    out.push('>');
+    out
+}
+
+fn inline_styles(html: &str, css: &str) -> String {
+    let rules = parse_rules(css);
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        out.push_str(&rest[..lt]);
+        let tail = &rest[lt..];
+
+        if tail.starts_with("</") || tail.starts_with("<!") {
+            match tail.find('>') {
+                Some(gt) => {
+                    out.push_str(&tail[..=gt]);
+                    rest = &tail[gt + 1..];
+                }
+                None => {
+                    out.push_str(tail);
+                    rest = "";
+                }
+            }
+            continue;
+        }
+
+        match find_tag_end(tail) {
+            Some(end) => {
+                out.push_str(&rewrite_tag(&tail[..=end], &rules));
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push_str(tail);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
 }
@@ -0,0 +1,18 @@
+use markup_css_once::{css_once_file, CssOnce};
+
+markup::define! {
+    Hello<'a>(css: &'a CssOnce, name: &'a str) {
+        @css_once_file!(css, "css_once_file.css")
+        p { "Hello, " @name }
+    }
+}
+
+#[test]
+fn loads_css_from_file_at_compile_time() {
+    let css = CssOnce::new();
+
+    let body = Hello { css: &css, name: "World" }.to_string();
+    assert_eq!(body, "<p>Hello, World</p>");
+
+    assert_eq!(css.render_styles(), "<style>p { color: teal }\n</style>\n");
+}
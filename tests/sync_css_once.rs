@@ -0,0 +1,26 @@
+use std::sync::Arc;
+use std::thread;
+
+use markup_css_once::{CssTracker, SyncCssOnce};
+
+struct Marker;
+
+#[test]
+fn exactly_one_racing_thread_sees_unrendered() {
+    let tracker = Arc::new(SyncCssOnce::new());
+
+    let threads: Vec<_> = (0..50)
+        .map(|_| {
+            let tracker = Arc::clone(&tracker);
+            thread::spawn(move || tracker.is_rendered::<Marker>())
+        })
+        .collect();
+
+    let unrendered = threads
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .filter(|was_rendered| !was_rendered)
+        .count();
+
+    assert_eq!(unrendered, 1);
+}